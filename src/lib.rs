@@ -1,11 +1,5 @@
 //! A library for cloning trait objects.
 //!
-//! ## Instability
-//!
-//! This library depends on an undocumented detail of fat pointer layouts.
-//!
-//! For that reason, this library is intentionally marked as unstable.
-//!
 //! ## Example
 //!
 //! ### Making a cloneable user-defined trait
@@ -71,14 +65,18 @@
 //! }
 //! ```
 
-// NOTE: this library doesn't explicitly use a library feature which is marked unstable.
-// Nonetheless it's intentionally made unstable because it relies on the internal detail
-// of fat pointer layouts.
-#![feature(rustc_private)]
+#![feature(ptr_metadata)]
+#![feature(try_reserve_kind)]
+#![cfg_attr(feature = "clone_to_uninit", feature(clone_to_uninit))]
 
-use std::alloc::{alloc, dealloc, Layout};
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+#[cfg(feature = "clone_to_uninit")]
+use std::clone::CloneToUninit;
+use std::collections::{TryReserveError, TryReserveErrorKind};
 use std::mem::forget;
-use std::ptr::write;
+use std::ptr::{self, write};
+use std::rc::Rc;
+use std::sync::Arc;
 
 /// A (possibly unsized) value which can be cloned into a pre-allocated space.
 ///
@@ -105,12 +103,64 @@ pub trait CloneIntoBox {
     unsafe fn clone_into_ptr(&self, ptr: *mut u8);
 }
 
+#[cfg(not(feature = "clone_to_uninit"))]
 impl<T: Clone> CloneIntoBox for T {
     unsafe fn clone_into_ptr(&self, ptr: *mut u8) {
         write(ptr as *mut T, self.clone())
     }
 }
 
+// Note for callers: `Box<[T]>` is itself `Sized` and `Clone` (via std's
+// `impl<T: Clone> Clone for Box<[T]>`), so `some_box.clone_into_box()`
+// resolves to the blanket `impl<T: Clone> CloneIntoBox for T` on the
+// `Box` itself and silently produces a `Box<Box<[T]>>`. As with `dyn
+// Trait` above, use `(*some_box).clone_into_box()` to target this impl.
+#[cfg(not(feature = "clone_to_uninit"))]
+impl<T: Clone> CloneIntoBox for [T] {
+    unsafe fn clone_into_ptr(&self, ptr: *mut u8) {
+        // Guards the already-cloned prefix so it gets dropped if a later
+        // `clone()` panics; `clone_into_box` will only `dealloc` the raw
+        // memory, not run element destructors.
+        struct Guard<T> {
+            base: *mut T,
+            initialized: usize,
+        }
+        impl<T> Drop for Guard<T> {
+            fn drop(&mut self) {
+                unsafe {
+                    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                        self.base,
+                        self.initialized,
+                    ));
+                }
+            }
+        }
+
+        let base = ptr as *mut T;
+        let mut guard = Guard { base, initialized: 0 };
+        for (i, item) in self.iter().enumerate() {
+            write(base.add(i), item.clone());
+            guard.initialized = i + 1;
+        }
+        forget(guard);
+    }
+}
+
+/// Derives `CloneIntoBox` from std's own `CloneToUninit`, when the
+/// `clone_to_uninit` crate feature is enabled.
+///
+/// `CloneToUninit` already has (or will have) correct, panic-safe impls
+/// for `T: Clone`, `[T]`, and `dyn Trait`/custom DSTs, so this single
+/// impl supersedes the `T: Clone`/`[T]` impls above and lets users who
+/// already implement `CloneToUninit` for their own unsized types plug
+/// straight into `clone_into_box` without a second trait impl.
+#[cfg(feature = "clone_to_uninit")]
+impl<T: CloneToUninit + ?Sized> CloneIntoBox for T {
+    unsafe fn clone_into_ptr(&self, ptr: *mut u8) {
+        self.clone_to_uninit(ptr)
+    }
+}
+
 /// An extension trait for cloning trait objects into `Box`es.
 ///
 /// ## Examples
@@ -123,6 +173,24 @@ pub trait CloneIntoBoxExt: CloneIntoBox {
     ///
     /// See [crate documentation](index.html) for examples.
     fn clone_into_box(&self) -> Box<Self> {
+        let layout = Layout::for_value::<Self>(self);
+        self.try_clone_into_box()
+            .unwrap_or_else(|_| handle_alloc_error(layout))
+    }
+
+    /// Clone the provided value into a `Box`-allocated space, returning
+    /// `Err` instead of aborting if the allocation fails.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use clone_into_box::{CloneIntoBox, CloneIntoBoxExt};
+    ///
+    /// let x = String::from("hello");
+    /// let y = x.try_clone_into_box().unwrap();
+    /// assert_eq!(*y, "hello");
+    /// ```
+    fn try_clone_into_box(&self) -> Result<Box<Self>, TryReserveError> {
         struct Guard {
             ptr: *mut u8,
             layout: Layout,
@@ -135,26 +203,151 @@ pub trait CloneIntoBoxExt: CloneIntoBox {
             }
         }
 
+        let meta = ptr::metadata(self as *const Self);
         let layout = Layout::for_value::<Self>(self);
-        let ptr = unsafe { alloc(layout) };
-        let guard = Guard { ptr, layout };
+        let thin = unsafe { alloc(layout) };
+        if thin.is_null() {
+            return Err(TryReserveErrorKind::AllocError {
+                layout,
+                non_exhaustive: (),
+            }
+            .into());
+        }
+        let guard = Guard { ptr: thin, layout };
         unsafe {
-            self.clone_into_ptr(ptr);
+            self.clone_into_ptr(thin);
         }
         forget(guard);
-        unsafe { Box::from_raw(assign_thin_mut(self, ptr)) }
+        Ok(unsafe { Box::from_raw(ptr::from_raw_parts_mut::<Self>(thin as *mut (), meta)) })
     }
 }
 impl<T: CloneIntoBox + ?Sized> CloneIntoBoxExt for T {}
 
-fn assign_thin_mut<T: ?Sized>(meta: *const T, thin: *mut u8) -> *mut T {
-    let mut fat = meta as *mut T;
-    // Assumes that the first *mut u8 is the thin pointer.
-    unsafe {
-        *(&mut fat as *mut *mut T as *mut *mut u8) = thin;
+/// A fallible counterpart of `Clone`, for types whose clone may fail,
+/// e.g. because it duplicates a scarce resource or performs I/O.
+pub trait TryClone: Sized {
+    /// The error produced by a failed clone.
+    type Error;
+
+    /// Attempt to clone `self`.
+    fn try_clone(&self) -> Result<Self, Self::Error>;
+}
+
+/// A (possibly unsized) value which can be cloned into a pre-allocated
+/// space, where the clone itself may fail.
+///
+/// This is the fallible counterpart of `CloneIntoBox`, for values whose
+/// clone can't be expressed through `Clone` (or `TryClone`'s blanket
+/// impl below) alone, such as `dyn Trait`s built on a user-defined
+/// fallible clone.
+pub trait TryCloneIntoBox {
+    /// The error produced by a failed clone.
+    type Error;
+
+    /// Clone into the specified place, or report why it couldn't be done.
+    ///
+    /// ## Effect
+    ///
+    /// On `Ok`, the area pointed to by `ptr` contains a valid
+    /// representation of `Self`, as in `CloneIntoBox::clone_into_ptr`.
+    /// On `Err`, `ptr` is left untouched and the caller must not treat it
+    /// as initialized.
+    ///
+    /// ## Safety
+    ///
+    /// The `ptr` parameter must point to an uninitialized area
+    /// which has enough space of `std::mem::size_of_val(self)` bytes
+    /// and is aligned to `std::mem::align_of_val(self)` bytes.
+    ///
+    /// ## Panics
+    ///
+    /// This method isn't expected to panic in normal cases,
+    /// but the caller must handle panics carefully for safety.
+    unsafe fn try_clone_into_ptr(&self, ptr: *mut u8) -> Result<(), Self::Error>;
+}
+
+impl<T: TryClone> TryCloneIntoBox for T {
+    type Error = T::Error;
+
+    unsafe fn try_clone_into_ptr(&self, ptr: *mut u8) -> Result<(), Self::Error> {
+        write(ptr as *mut T, self.try_clone()?);
+        Ok(())
+    }
+}
+
+/// An extension trait for fallibly cloning trait objects into `Box`es.
+pub trait TryCloneIntoBoxExt: TryCloneIntoBox {
+    /// Clone the provided value into a `Box`-allocated space, or return
+    /// the error reported by the underlying fallible clone.
+    fn try_clone_into_box(&self) -> Result<Box<Self>, Self::Error> {
+        struct Guard {
+            ptr: *mut u8,
+            layout: Layout,
+        }
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                unsafe {
+                    dealloc(self.ptr, self.layout);
+                }
+            }
+        }
+
+        let meta = ptr::metadata(self as *const Self);
+        let layout = Layout::for_value::<Self>(self);
+        let thin = unsafe { alloc(layout) };
+        if thin.is_null() {
+            // `Self::Error` has no allocator-failure variant to report
+            // through, so fall back to aborting, same as the infallible
+            // `CloneIntoBoxExt::clone_into_box` does.
+            handle_alloc_error(layout);
+        }
+        let guard = Guard { ptr: thin, layout };
+        // `guard` deallocates the untouched region if `try_clone_into_ptr`
+        // returns `Err` or panics.
+        match unsafe { self.try_clone_into_ptr(thin) } {
+            Ok(()) => {
+                forget(guard);
+                Ok(unsafe { Box::from_raw(ptr::from_raw_parts_mut::<Self>(thin as *mut (), meta)) })
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+impl<T: TryCloneIntoBox + ?Sized> TryCloneIntoBoxExt for T {}
+
+/// An extension trait providing `Rc::make_mut`/`Arc::make_mut`-style
+/// in-place mutation for shared, boxed `dyn Trait` values.
+///
+/// Std only implements this pattern for `T: Clone` (and, more recently,
+/// unsized `T: CloneToUninit`). This trait generalizes it to any
+/// `CloneIntoBox`, so a `Rc<dyn Trait>`/`Arc<dyn Trait>` can be mutated
+/// in place without the caller hand-rolling the clone-on-write dance.
+pub trait MakeMutExt: CloneIntoBox {
+    /// Get mutable access to `this`, cloning the referent into a fresh
+    /// `Rc` first if it is shared with other `Rc`s or `Weak`s.
+    fn make_mut(this: &mut Rc<Self>) -> &mut Self {
+        if Rc::get_mut(this).is_none() {
+            *this = Rc::from((**this).clone_into_box());
+        }
+        // `get_mut` cannot fail now: `this` was either unique already or
+        // has just been replaced with a freshly allocated, uniquely-owned
+        // `Rc`.
+        Rc::get_mut(this).unwrap()
+    }
+
+    /// The `Arc` counterpart of [`make_mut`](MakeMutExt::make_mut).
+    fn make_mut_arc(this: &mut Arc<Self>) -> &mut Self {
+        if Arc::get_mut(this).is_none() {
+            *this = Arc::from((**this).clone_into_box());
+        }
+        // Same reasoning as `make_mut`: `Arc::get_mut` already accounts
+        // for the weak-but-not-strong race, so relying on it here (rather
+        // than re-deriving `ArcInner`'s private header layout) inherits
+        // that correctness for free.
+        Arc::get_mut(this).unwrap()
     }
-    fat
 }
+impl<T: CloneIntoBox + ?Sized> MakeMutExt for T {}
 
 #[cfg(test)]
 mod tests {
@@ -196,4 +389,200 @@ mod tests {
         assert_eq!(f(), "Hello, world!");
         let _ = f.clone();
     }
+
+    #[test]
+    fn test_clone_slice() {
+        let s: Box<[i32]> = vec![1, 2, 3].into_boxed_slice();
+        // Use (*s) to target `impl CloneIntoBox for [T]` rather than the
+        // blanket impl on `Box<[T]>` itself (which is also `Clone`).
+        let t = (*s).clone_into_box();
+        assert_eq!(&*t, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_clone_slice_panic() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct DropCounter(i32, Rc<Cell<usize>>);
+        impl Clone for DropCounter {
+            fn clone(&self) -> Self {
+                if self.0 == 2 {
+                    panic!("DropCounter::clone() is called for index 2");
+                }
+                DropCounter(self.0, Rc::clone(&self.1))
+            }
+        }
+        impl Drop for DropCounter {
+            fn drop(&mut self) {
+                self.1.set(self.1.get() + 1);
+            }
+        }
+
+        let drops = Rc::new(Cell::new(0));
+        let s: Box<[DropCounter]> = vec![
+            DropCounter(0, Rc::clone(&drops)),
+            DropCounter(1, Rc::clone(&drops)),
+            DropCounter(2, Rc::clone(&drops)),
+        ]
+        .into_boxed_slice();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            // Use (*s) to target `impl CloneIntoBox for [T]` rather than
+            // the blanket impl on `Box<[T]>` itself (which is also `Clone`).
+            let _ = (*s).clone_into_box();
+        }));
+        assert!(result.is_err());
+        // Only the two already-cloned elements (index 0 and 1) should
+        // have been dropped by the guard; `s` itself is still alive.
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn test_make_mut_rc() {
+        trait Greeter: CloneIntoBox {
+            fn greeting(&self) -> String;
+            fn set_name(&mut self, name: String);
+        }
+
+        #[derive(Clone)]
+        struct Foo(String);
+        impl Greeter for Foo {
+            fn greeting(&self) -> String {
+                format!("Hello, {}!", self.0)
+            }
+            fn set_name(&mut self, name: String) {
+                self.0 = name;
+            }
+        }
+
+        let mut x: Rc<dyn Greeter> = Rc::new(Foo(String::from("John")));
+        let y = Rc::clone(&x);
+        assert_eq!(y.greeting(), "Hello, John!");
+
+        MakeMutExt::make_mut(&mut x).set_name(String::from("Jane"));
+        assert_eq!(x.greeting(), "Hello, Jane!");
+        assert_eq!(y.greeting(), "Hello, John!");
+    }
+
+    #[test]
+    fn test_make_mut_arc() {
+        trait Greeter: CloneIntoBox + Send + Sync {
+            fn greeting(&self) -> String;
+            fn set_name(&mut self, name: String);
+        }
+
+        #[derive(Clone)]
+        struct Foo(String);
+        impl Greeter for Foo {
+            fn greeting(&self) -> String {
+                format!("Hello, {}!", self.0)
+            }
+            fn set_name(&mut self, name: String) {
+                self.0 = name;
+            }
+        }
+
+        let mut x: Arc<dyn Greeter> = Arc::new(Foo(String::from("John")));
+        let y = Arc::clone(&x);
+        assert_eq!(y.greeting(), "Hello, John!");
+
+        MakeMutExt::make_mut_arc(&mut x).set_name(String::from("Jane"));
+        assert_eq!(x.greeting(), "Hello, Jane!");
+        assert_eq!(y.greeting(), "Hello, John!");
+    }
+
+    #[test]
+    fn test_try_clone_into_box() {
+        #[derive(Debug, PartialEq)]
+        struct Odd(i32);
+        impl TryClone for Odd {
+            type Error = &'static str;
+            fn try_clone(&self) -> Result<Self, Self::Error> {
+                if self.0 % 2 == 0 {
+                    Err("cannot clone an even Odd")
+                } else {
+                    Ok(Odd(self.0))
+                }
+            }
+        }
+
+        let x = Odd(1);
+        let y = x.try_clone_into_box().unwrap();
+        assert_eq!(*y, Odd(1));
+
+        let z = Odd(2);
+        assert_eq!(z.try_clone_into_box().unwrap_err(), "cannot clone an even Odd");
+    }
+
+    #[test]
+    fn test_try_clone_into_box_trait_object() {
+        // The motivating use case: a `dyn Trait` whose clone can't be
+        // expressed through `Clone`/`TryClone`, so it implements
+        // `TryCloneIntoBox` manually instead of via the blanket impl.
+        trait FallibleGreeter: TryCloneIntoBox<Error = &'static str> {
+            fn greeting(&self) -> String;
+        }
+
+        struct Foo {
+            name: String,
+            poisoned: bool,
+        }
+        impl FallibleGreeter for Foo {
+            fn greeting(&self) -> String {
+                format!("Hello, {}!", self.name)
+            }
+        }
+        impl TryCloneIntoBox for Foo {
+            type Error = &'static str;
+            unsafe fn try_clone_into_ptr(&self, ptr: *mut u8) -> Result<(), Self::Error> {
+                if self.poisoned {
+                    return Err("cannot clone a poisoned Foo");
+                }
+                write(
+                    ptr as *mut Foo,
+                    Foo {
+                        name: self.name.clone(),
+                        poisoned: self.poisoned,
+                    },
+                );
+                Ok(())
+            }
+        }
+
+        let x: Box<dyn FallibleGreeter> = Box::new(Foo {
+            name: String::from("John"),
+            poisoned: false,
+        });
+        let y = (*x).try_clone_into_box().unwrap();
+        assert_eq!(y.greeting(), "Hello, John!");
+
+        let z: Box<dyn FallibleGreeter> = Box::new(Foo {
+            name: String::from("Jane"),
+            poisoned: true,
+        });
+        assert_eq!(
+            (*z).try_clone_into_box().unwrap_err(),
+            "cannot clone a poisoned Foo"
+        );
+    }
+
+    #[cfg(feature = "clone_to_uninit")]
+    #[test]
+    fn test_clone_to_uninit_feature() {
+        // Exercises the `CloneToUninit`-derived `CloneIntoBox` impl
+        // (rather than the default `T: Clone`/`[T]` impls, which are
+        // `cfg`'d out under this feature) through both `clone_into_box`
+        // and `try_clone_into_box`.
+        let s: Box<[i32]> = vec![1, 2, 3].into_boxed_slice();
+        // Use (*s) to target the `CloneToUninit`-derived impl on `[T]`
+        // rather than the one on `Box<[T]>` itself (which is also
+        // `CloneToUninit`, since it is `Clone`).
+        let t = (*s).clone_into_box();
+        assert_eq!(&*t, &[1, 2, 3]);
+
+        let x = String::from("hello");
+        let y = x.try_clone_into_box().unwrap();
+        assert_eq!(*y, "hello");
+    }
 }